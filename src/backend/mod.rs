@@ -0,0 +1,278 @@
+//! Backend-agnostic monitor configuration.
+//!
+//! `Backend` is the seam between "what the user wants" (`crate::config`) and
+//! "how to actually make the screen show it", so the same config and the
+//! same transaction/diffing logic work whether we're talking to an X server
+//! via `xrandr` or to a DRM device directly.
+
+pub mod drm;
+pub mod x11;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/// A monitor rotation, independent of the backend used to apply it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum Rotation {
+    #[default]
+    Normal,
+    Left,
+    Inverted,
+    Right,
+}
+
+/// Refresh rates within this many Hz of each other are considered the same
+/// mode, since reported rates rarely match an exact request bit-for-bit.
+const REFRESH_RATE_TOLERANCE: f64 = 1.0;
+
+/// A display mode, with limited equality scope: whether it's the same size
+/// and (approximately) the same refresh rate, not whether it's the exact
+/// same backend mode object.
+#[derive(Debug, Clone)]
+pub struct ModeInfo {
+    /// Backend-specific identifier (an xrandr mode xid, or an index into a
+    /// DRM connector's mode list), used only to break ties deterministically
+    /// during selection and to look the concrete mode back up when applying.
+    pub id: u64,
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: f64,
+}
+
+impl PartialEq for ModeInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && f64::abs(self.refresh_rate - other.refresh_rate) < REFRESH_RATE_TOLERANCE
+    }
+}
+impl Eq for ModeInfo {}
+
+/// Identifies a monitor within whichever backend produced it.
+#[derive(Debug, Clone)]
+pub enum MonitorHandle {
+    X11 { output_name: String },
+    Drm { connector_id: u32 },
+}
+
+/// A monitor as enumerated by a backend, before any config has been applied
+/// to it.
+#[derive(Debug, Clone)]
+pub struct MonitorInfo {
+    pub handle: MonitorHandle,
+    pub name: String,
+    pub edid: Option<Vec<u8>>,
+    pub available_modes: Vec<ModeInfo>,
+}
+
+/// Parses a monitor's raw EDID blob.
+pub fn parse_edid(raw: &[u8]) -> anyhow::Result<edid::EDID> {
+    match edid::parse(raw) {
+        nom::IResult::Done(_, edid_value) => Ok(edid_value),
+        e => anyhow::bail!("Failed to parse EDID: {:?}", e),
+    }
+}
+
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct Diff {
+    pub primary: Option<bool>,
+    pub position: Option<(i32, i32)>,
+    pub rotation: Option<Rotation>,
+    pub mode: Option<ModeInfo>,
+}
+
+/// Snapshot of a monitor's CRTC state before any change is applied, kept
+/// around so a transaction can roll back to it if a later item fails to
+/// apply.
+#[derive(Debug, Clone)]
+pub struct CrtcSnapshot {
+    pub position: (i32, i32),
+    pub rotation: Rotation,
+    pub mode: Option<ModeInfo>,
+    pub primary: bool,
+}
+
+#[derive(Debug)]
+pub struct TransactionItem {
+    pub handle: MonitorHandle,
+    pub name: String,
+    pub diff: Diff,
+    pub original: CrtcSnapshot,
+}
+
+/// Every monitor's desired change for one reconfiguration pass, computed up
+/// front so it can be committed as a single all-or-nothing unit rather than
+/// issuing independent calls per monitor that could leave a half-applied
+/// layout on failure.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub items: Vec<TransactionItem>,
+}
+
+impl std::fmt::Display for Transaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.items.is_empty() {
+            return writeln!(f, "(no changes)");
+        }
+        for item in &self.items {
+            writeln!(f, "{}: {:?}", item.name, item.diff)?;
+        }
+        Ok(())
+    }
+}
+
+/// The pixel dimensions of the smallest bounding box containing every
+/// item's post-transaction position and mode (falling back to its current
+/// position/mode where the diff doesn't change them). Backends with a
+/// single combined screen that CRTCs are placed within (X11) need this to
+/// grow the screen to fit a new layout before placing any CRTC outside its
+/// current bounds; backends without that concept (DRM) have no use for it.
+pub fn bounding_box(transaction: &Transaction) -> Option<(u32, u32)> {
+    transaction
+        .items
+        .iter()
+        .filter_map(|item| {
+            let (x, y) = item.diff.position.unwrap_or(item.original.position);
+            let mode = item.diff.mode.as_ref().or(item.original.mode.as_ref())?;
+            let rotation = item.diff.rotation.unwrap_or(item.original.rotation);
+            let (width, height) = match rotation {
+                Rotation::Normal | Rotation::Inverted => (mode.width, mode.height),
+                Rotation::Left | Rotation::Right => (mode.height, mode.width),
+            };
+            Some(((x.max(0) as u32).saturating_add(width), (y.max(0) as u32).saturating_add(height)))
+        })
+        .reduce(|(aw, ah), (bw, bh)| (aw.max(bw), ah.max(bh)))
+}
+
+/// A source of monitor state and the sink changes are committed to. Abstracted
+/// so a KMS backend can offer a true atomic modeset where X has to emulate
+/// one.
+pub trait Backend {
+    fn enumerate_monitors(&mut self) -> anyhow::Result<Vec<MonitorInfo>>;
+    fn current_state(&mut self, handle: &MonitorHandle) -> anyhow::Result<CrtcSnapshot>;
+    /// Commits every item in `transaction` as a single all-or-nothing unit.
+    fn apply(&mut self, transaction: &Transaction) -> anyhow::Result<()>;
+}
+
+fn compute_diff(
+    spec: &crate::config::MonitorSpec,
+    available_modes: &[ModeInfo],
+    current: &CrtcSnapshot,
+) -> anyhow::Result<Diff> {
+    let mut diff = Diff::default();
+    if spec.primary && !current.primary {
+        diff.primary = Some(true);
+    }
+    if current.rotation != spec.rotation {
+        diff.rotation = Some(spec.rotation);
+    }
+    if current.position != (spec.x, spec.y) {
+        diff.position = Some((spec.x, spec.y));
+    }
+
+    let Some(best_mode) = spec.select_mode(available_modes) else {
+        anyhow::bail!("unable to find compatible mode among {:?}", available_modes);
+    };
+    if current.mode.as_ref().is_none_or(|m| m != best_mode) {
+        diff.mode = Some(best_mode.clone());
+    }
+    Ok(diff)
+}
+
+/// Builds the transaction for one pass over `config`: selects the profile
+/// that best matches the currently connected monitors, computes each
+/// referenced monitor's diff against it, and disables every connected
+/// monitor the chosen profile doesn't reference.
+pub fn build_transaction(backend: &mut dyn Backend, config: &Config) -> anyhow::Result<Transaction> {
+    let monitors = backend.enumerate_monitors()?;
+    let mut transaction = Transaction::default();
+
+    let Some((profile_name, profile)) = crate::config::select_profile(config, &monitors) else {
+        println!("No profile configured");
+        return Ok(transaction);
+    };
+    println!("Selected profile {profile_name:?}");
+
+    for monitor in monitors {
+        let current = match backend.current_state(&monitor.handle) {
+            Ok(current) => current,
+            Err(e) => {
+                println!("Monitor {}: {:?}", monitor.name, e);
+                continue;
+            }
+        };
+
+        let diff = match crate::config::resolve_monitor_spec(profile, &monitor) {
+            Some(spec) => match compute_diff(spec, &monitor.available_modes, &current) {
+                Ok(diff) => diff,
+                Err(e) => {
+                    println!("Monitor {}: {:?}", monitor.name, e);
+                    continue;
+                }
+            },
+            // Not referenced by the active profile: disable it, unless it's
+            // already disabled (no mode currently set). An unconditional
+            // `Some(false)` here would make every disabled, unreferenced
+            // monitor show up in every transaction on every re-evaluation
+            // (SIGHUP reload, repeated invocation), relying on every
+            // backend's disable being an idempotent no-op rather than
+            // actually being one.
+            None if current.mode.is_some() => Diff { primary: Some(false), ..Diff::default() },
+            None => Diff::default(),
+        };
+
+        if diff == Diff::default() {
+            continue;
+        }
+        transaction.items.push(TransactionItem {
+            handle: monitor.handle,
+            name: monitor.name,
+            diff,
+            original: current,
+        });
+    }
+    Ok(transaction)
+}
+
+pub fn apply_config(backend: &mut dyn Backend, config: &Config, dry_run: bool) -> anyhow::Result<()> {
+    let transaction = build_transaction(backend, config)?;
+    if dry_run {
+        print!("{transaction}");
+        return Ok(());
+    }
+    if transaction.items.is_empty() {
+        return Ok(());
+    }
+    println!("{transaction}");
+    backend.apply(&transaction)
+}
+
+/// Which backend to configure monitors through.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum BackendKind {
+    X11,
+    Drm,
+}
+
+/// Resolves the `--backend` override if given, else X11 if `$DISPLAY` is
+/// set, else DRM. Exposed separately from `open_backend` so callers can
+/// check which backend would be chosen without opening it.
+pub fn resolve_backend_kind(kind: Option<BackendKind>) -> BackendKind {
+    kind.unwrap_or_else(|| {
+        if std::env::var_os("DISPLAY").is_some() {
+            BackendKind::X11
+        } else {
+            BackendKind::Drm
+        }
+    })
+}
+
+/// Picks a backend: the explicit `--backend` override if given, else X11 if
+/// `$DISPLAY` is set, else DRM.
+pub fn open_backend(kind: Option<BackendKind>) -> anyhow::Result<Box<dyn Backend>> {
+    match resolve_backend_kind(kind) {
+        BackendKind::X11 => Ok(Box::new(x11::XrandrBackend::open()?)),
+        BackendKind::Drm => Ok(Box::new(drm::DrmBackend::open()?)),
+    }
+}
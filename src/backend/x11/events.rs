@@ -0,0 +1,92 @@
+//! Thin FFI bindings over libX11/libXrandr for RandR change notifications.
+//!
+//! The `xrandr` crate only wraps the configuration-query/apply side of
+//! libXrandr and has no way to select for or receive events, so we open a
+//! second, dedicated Xlib connection purely to watch for screen-change
+//! events. Monitor configuration itself keeps going through
+//! `xrandr::XHandle` as before.
+
+use std::ffi::c_void;
+use std::os::raw::{c_int, c_uchar, c_ulong};
+use std::os::unix::io::RawFd;
+
+use anyhow::bail;
+
+#[allow(non_camel_case_types)]
+type Display = c_void;
+#[allow(non_camel_case_types)]
+type Window = c_ulong;
+
+const RR_SCREEN_CHANGE_NOTIFY_MASK: c_ulong = 1 << 0;
+const RR_CRTC_CHANGE_NOTIFY_MASK: c_ulong = 1 << 1;
+const RR_OUTPUT_CHANGE_NOTIFY_MASK: c_ulong = 1 << 2;
+
+#[repr(C)]
+struct XEvent {
+    // Xlib's XEvent is a union big enough to hold any concrete event type.
+    // We never inspect its fields ourselves, only hand the buffer to
+    // XNextEvent/XRRUpdateConfiguration, so an opaque byte array sized
+    // generously above the largest known event struct is enough.
+    _opaque: [u8; 192],
+}
+
+extern "C" {
+    fn XOpenDisplay(display_name: *const c_uchar) -> *mut Display;
+    fn XCloseDisplay(display: *mut Display) -> c_int;
+    fn XDefaultRootWindow(display: *mut Display) -> Window;
+    fn XConnectionNumber(display: *mut Display) -> c_int;
+    fn XPending(display: *mut Display) -> c_int;
+    fn XNextEvent(display: *mut Display, event: *mut XEvent) -> c_int;
+
+    fn XRRSelectInput(display: *mut Display, window: Window, mask: c_int);
+    fn XRRUpdateConfiguration(event: *mut XEvent);
+}
+
+/// Watches for RandR topology changes (hotplug, dock/undock, resolution
+/// changes) on a dedicated Xlib connection.
+pub struct RandrWatcher {
+    display: *mut Display,
+}
+
+impl RandrWatcher {
+    pub fn open() -> anyhow::Result<Self> {
+        let display = unsafe { XOpenDisplay(std::ptr::null()) };
+        if display.is_null() {
+            bail!("XOpenDisplay failed, is $DISPLAY set?");
+        }
+        let root = unsafe { XDefaultRootWindow(display) };
+        let mask =
+            RR_SCREEN_CHANGE_NOTIFY_MASK | RR_CRTC_CHANGE_NOTIFY_MASK | RR_OUTPUT_CHANGE_NOTIFY_MASK;
+        unsafe { XRRSelectInput(display, root, mask as c_int) };
+        Ok(Self { display })
+    }
+
+    /// The connection's file descriptor, suitable for `poll()`.
+    pub fn fd(&self) -> RawFd {
+        unsafe { XConnectionNumber(self.display) }
+    }
+
+    /// Drains every currently-pending event, feeding each through
+    /// `XRRUpdateConfiguration` so libXrandr's cached screen info stays
+    /// current. Returns whether any event was seen.
+    pub fn drain_events(&self) -> bool {
+        let mut seen = false;
+        let mut event = XEvent { _opaque: [0; 192] };
+        while unsafe { XPending(self.display) } > 0 {
+            unsafe {
+                XNextEvent(self.display, &mut event);
+                XRRUpdateConfiguration(&mut event);
+            }
+            seen = true;
+        }
+        seen
+    }
+}
+
+impl Drop for RandrWatcher {
+    fn drop(&mut self) {
+        unsafe {
+            XCloseDisplay(self.display);
+        }
+    }
+}
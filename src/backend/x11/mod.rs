@@ -0,0 +1,340 @@
+//! The `xrandr`-backed `Backend` implementation, for running under a
+//! running X server.
+
+mod events;
+mod screen;
+
+use anyhow::{Context, bail};
+
+use crate::backend::{Backend, CrtcSnapshot, ModeInfo, MonitorHandle, MonitorInfo, Rotation, Transaction, TransactionItem};
+
+/// How long the event connection must stay quiet before the diff is
+/// recomputed and applied. Each further event arriving within this window of
+/// the previous one restarts it, so a single hotplug that fires several RandR
+/// events in quick succession only triggers one reconfiguration.
+const DEBOUNCE_MILLIS: i32 = 200;
+
+pub struct XrandrBackend {
+    xhandle: xrandr::XHandle,
+}
+
+impl XrandrBackend {
+    pub fn open() -> anyhow::Result<Self> {
+        Ok(Self {
+            xhandle: xrandr::XHandle::open()?,
+        })
+    }
+
+    /// Re-resolves an output by connector name. We can't hold on to a
+    /// borrowed `xrandr::Output` across calls (it borrows from a
+    /// `ScreenResources`/monitor list we re-fetch each time), so
+    /// `MonitorHandle::X11` only stores the name and every operation looks
+    /// the output up again.
+    fn find_output(&mut self, name: &str) -> anyhow::Result<xrandr::Output> {
+        self.xhandle
+            .monitors()?
+            .into_iter()
+            .flat_map(|m| m.outputs)
+            .find(|o| o.name == name)
+            .with_context(|| format!("output {name} is no longer present"))
+    }
+
+    fn apply_item(&mut self, item: &TransactionItem) -> anyhow::Result<()> {
+        let MonitorHandle::X11 { output_name } = &item.handle else {
+            bail!("XrandrBackend given a non-X11 transaction item");
+        };
+        let output = self.find_output(output_name)?;
+        match item.diff.primary {
+            // TODO: why doesn't this have an error return type?
+            Some(true) => self.xhandle.set_primary(&output),
+            Some(false) => self.disable_output(&output)?,
+            None => {}
+        }
+        if let Some((x, y)) = item.diff.position {
+            self.xhandle.set_absolute_position(&output, x, y)?;
+        }
+        if let Some(rotation) = item.diff.rotation {
+            self.xhandle.set_rotation(&output, &to_xrandr_rotation(rotation))?;
+        }
+        if let Some(mode) = &item.diff.mode {
+            let xrandr_mode = self.find_mode(mode.id)?;
+            self.xhandle.set_mode(&output, &xrandr_mode)?;
+        }
+        Ok(())
+    }
+
+    fn rollback_item(&mut self, item: &TransactionItem) -> anyhow::Result<()> {
+        let MonitorHandle::X11 { output_name } = &item.handle else {
+            bail!("XrandrBackend given a non-X11 transaction item");
+        };
+        let output = self.find_output(output_name)?;
+        self.xhandle
+            .set_absolute_position(&output, item.original.position.0, item.original.position.1)?;
+        self.xhandle
+            .set_rotation(&output, &to_xrandr_rotation(item.original.rotation))?;
+        if let Some(mode) = &item.original.mode {
+            let xrandr_mode = self.find_mode(mode.id)?;
+            self.xhandle.set_mode(&output, &xrandr_mode)?;
+        }
+        Ok(())
+    }
+
+    /// Name of the output currently marked primary, if any. X only ever has
+    /// one primary output, so marking a different one primary implicitly
+    /// un-primaries this one - which is why restoring it on rollback has to
+    /// be done once per transaction rather than per item (an item for the
+    /// previously-primary output may not even be part of the transaction).
+    fn primary_output_name(&mut self) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .xhandle
+            .monitors()?
+            .into_iter()
+            .find(|m| m.primary)
+            .and_then(|m| m.outputs.into_iter().next())
+            .map(|o| o.name))
+    }
+
+    /// Turns off the CRTC driving `output`, if any. Used for monitors that
+    /// the active profile doesn't reference.
+    fn disable_output(&mut self, output: &xrandr::Output) -> anyhow::Result<()> {
+        let Some(crtc_id) = output.crtc else {
+            // Already disabled.
+            return Ok(());
+        };
+        let sr = xrandr::ScreenResources::new(&mut self.xhandle)?;
+        let crtc = sr.crtc(&mut self.xhandle, crtc_id)?;
+        crtc.disable(&mut self.xhandle)
+    }
+
+    fn find_mode(&mut self, xid: u64) -> anyhow::Result<xrandr::Mode> {
+        let sr = xrandr::ScreenResources::new(&mut self.xhandle)?;
+        sr.modes
+            .iter()
+            .find(|m| m.xid == xid)
+            .cloned()
+            .with_context(|| format!("mode {xid} is no longer present"))
+    }
+}
+
+impl Backend for XrandrBackend {
+    fn enumerate_monitors(&mut self) -> anyhow::Result<Vec<MonitorInfo>> {
+        let sr = xrandr::ScreenResources::new(&mut self.xhandle)?;
+        let available_modes: Vec<_> = sr
+            .modes
+            .iter()
+            .map(|m| ModeInfo {
+                id: m.xid,
+                width: m.width,
+                height: m.height,
+                refresh_rate: m.rate,
+            })
+            .collect();
+
+        let mut monitors = Vec::new();
+        for monitor in self.xhandle.monitors()? {
+            let [output] = &monitor.outputs[..] else {
+                println!(
+                    "Monitor {}: has >1 output: {:?}",
+                    monitor.name,
+                    monitor.outputs.iter().map(|o| &o.name)
+                );
+                continue;
+            };
+            monitors.push(MonitorInfo {
+                handle: MonitorHandle::X11 { output_name: output.name.clone() },
+                name: output.name.clone(),
+                edid: output.edid(),
+                available_modes: available_modes.clone(),
+            });
+        }
+        Ok(monitors)
+    }
+
+    fn current_state(&mut self, handle: &MonitorHandle) -> anyhow::Result<CrtcSnapshot> {
+        let MonitorHandle::X11 { output_name } = handle else {
+            bail!("XrandrBackend given a non-X11 monitor handle");
+        };
+        // Fetched once and used for both the output and its monitor's
+        // `primary` flag, rather than a second independent `monitors()` call.
+        let monitor = self
+            .xhandle
+            .monitors()?
+            .into_iter()
+            .find(|m| m.outputs.iter().any(|o| o.name == *output_name))
+            .with_context(|| format!("output {output_name} is no longer present"))?;
+        let output = monitor
+            .outputs
+            .iter()
+            .find(|o| o.name == *output_name)
+            .expect("monitor matched by this output's name contains it");
+
+        let sr = xrandr::ScreenResources::new(&mut self.xhandle)?;
+        let Some(crtc_id) = output.crtc else {
+            bail!(
+                "required exactly 1 CRTC associated with output {}, got {:?} and {:?}",
+                output.name,
+                output.crtc,
+                output.crtcs
+            );
+        };
+        let crtc = sr.crtc(&mut self.xhandle, crtc_id)?;
+        let mode = output.current_mode.and_then(|id| sr.modes.iter().find(|m| m.xid == id)).map(|m| ModeInfo {
+            id: m.xid,
+            width: m.width,
+            height: m.height,
+            refresh_rate: m.rate,
+        });
+        Ok(CrtcSnapshot {
+            position: (crtc.x, crtc.y),
+            rotation: from_xrandr_rotation(crtc.rotation),
+            primary: monitor.primary,
+            mode,
+        })
+    }
+
+    fn apply(&mut self, transaction: &Transaction) -> anyhow::Result<()> {
+        // xrandr has no atomic multi-CRTC commit, so all-or-nothing is
+        // emulated: apply items one at a time and roll everything
+        // already-applied back if a later one fails. The primary output is
+        // restored separately from the rest of a rolled-back item's state,
+        // since X only allows one primary output at a time: an item that
+        // marks its own output primary implicitly un-primaries whichever
+        // output held it before, even if that output isn't itself part of
+        // this transaction. The screen itself is grown to fit the new
+        // layout's bounding box first, since placing a CRTC outside the
+        // screen's current bounds fails with BadMatch.
+        if let Some((width, height)) = crate::backend::bounding_box(transaction) {
+            screen::grow_screen_to_fit(width, height).context("growing X screen to fit new layout")?;
+        }
+
+        let original_primary = self.primary_output_name()?;
+        let mut applied = Vec::new();
+        for item in &transaction.items {
+            match self.apply_item(item) {
+                Ok(()) => applied.push(item),
+                Err(e) => {
+                    for rollback_item in applied.into_iter().rev() {
+                        if let Err(rollback_err) = self.rollback_item(rollback_item) {
+                            eprintln!(
+                                "failed to roll back {} after a failed commit: {:?}",
+                                rollback_item.name, rollback_err
+                            );
+                        }
+                    }
+                    if let Some(name) = &original_primary {
+                        match self.find_output(name) {
+                            // TODO: why doesn't this have an error return type?
+                            Ok(output) => self.xhandle.set_primary(&output),
+                            Err(find_err) => {
+                                eprintln!("failed to restore primary output {name} after a failed commit: {find_err:?}")
+                            }
+                        }
+                    }
+                    return Err(e.context(format!("applying change to {}", item.name)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn from_xrandr_rotation(r: xrandr::Rotation) -> Rotation {
+    match r {
+        xrandr::Rotation::Normal => Rotation::Normal,
+        xrandr::Rotation::Left => Rotation::Left,
+        xrandr::Rotation::Inverted => Rotation::Inverted,
+        xrandr::Rotation::Right => Rotation::Right,
+    }
+}
+
+fn to_xrandr_rotation(r: Rotation) -> xrandr::Rotation {
+    match r {
+        Rotation::Normal => xrandr::Rotation::Normal,
+        Rotation::Left => xrandr::Rotation::Left,
+        Rotation::Inverted => xrandr::Rotation::Inverted,
+        Rotation::Right => xrandr::Rotation::Right,
+    }
+}
+
+/// Blocks on both the RandR event connection's file descriptor and the
+/// daemon's signal self-pipe, re-applying the config at `config_path`
+/// whenever the display topology changes, on `SIGHUP`, and once up front.
+/// Bursts of RandR events that arrive within `DEBOUNCE_MILLIS` of each other
+/// are coalesced into a single reconfiguration. Returns once `SIGTERM` or
+/// `SIGINT` is received.
+pub fn run_watch_loop(backend: &mut XrandrBackend, config_path: &std::path::Path, dry_run: bool) -> anyhow::Result<()> {
+    let watcher = events::RandrWatcher::open().context("opening RandR event connection")?;
+    let randr_fd = watcher.fd();
+    let signal_fd = crate::daemon::install_signal_pipe().context("installing signal handlers")?;
+
+    // Unlike later reloads, a failure here should stop the daemon from
+    // starting at all rather than silently sitting in the loop doing
+    // nothing.
+    let config = crate::config::load_config(config_path)?;
+    crate::backend::apply_config(backend, &config, dry_run)?;
+
+    loop {
+        let mut pollfds = [
+            libc::pollfd { fd: randr_fd, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: signal_fd, events: libc::POLLIN, revents: 0 },
+        ];
+        if unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, -1) } < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EINTR) {
+                // poll() isn't restarted across a signal delivery (it's
+                // explicitly excluded from SA_RESTART, see signal(7)), but
+                // that's exactly when we have a signal waiting in the
+                // self-pipe - just go round again and pick it up below.
+                continue;
+            }
+            bail!("poll() on watch fds failed: {err}");
+        }
+
+        if pollfds[1].revents & libc::POLLIN != 0 {
+            let signals = crate::daemon::drain_signals(signal_fd);
+            if signals.iter().any(|&s| s == libc::SIGTERM || s == libc::SIGINT) {
+                println!("Received termination signal, shutting down");
+                return Ok(());
+            }
+            if signals.contains(&libc::SIGHUP) {
+                println!("Received SIGHUP, reloading config");
+                reload_and_apply(backend, config_path, dry_run);
+            }
+        }
+
+        if pollfds[0].revents & libc::POLLIN != 0 && watcher.drain_events() {
+            // Debounce: keep draining any further events that arrive in
+            // quick succession before reacting.
+            loop {
+                let mut pollfd = libc::pollfd {
+                    fd: randr_fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                if unsafe { libc::poll(&mut pollfd, 1, DEBOUNCE_MILLIS) } <= 0 {
+                    break;
+                }
+                watcher.drain_events();
+            }
+
+            println!("Display topology changed, re-applying config");
+            reload_and_apply(backend, config_path, dry_run);
+        }
+    }
+}
+
+/// Re-reads the config from `config_path` and applies it, logging (rather
+/// than propagating) any failure so a bad edit or a transient backend error
+/// doesn't bring down the running daemon.
+fn reload_and_apply(backend: &mut XrandrBackend, config_path: &std::path::Path, dry_run: bool) {
+    let config = match crate::config::load_config(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Failed to reload config: {e:?}");
+            return;
+        }
+    };
+    if let Err(e) = crate::backend::apply_config(backend, &config, dry_run) {
+        println!("Failed to apply config: {e:?}");
+    }
+}
@@ -0,0 +1,79 @@
+//! Thin FFI bindings over libX11/libXrandr for resizing the X screen itself.
+//!
+//! The `xrandr` crate has no equivalent of `XRRSetScreenSize`: it can place
+//! CRTCs anywhere within the screen's current bounding box, but can't grow
+//! that box, and `XRRSetCrtcConfig` (what `set_absolute_position`/`set_mode`
+//! compile down to) fails with `BadMatch` if a CRTC's position plus mode
+//! would fall outside it. So before placing any CRTC outside the current
+//! screen size, we open a second, dedicated Xlib connection purely to grow
+//! it, same as `events.rs` does for RandR event selection.
+
+use std::ffi::c_void;
+use std::os::raw::{c_int, c_uchar, c_ulong};
+
+use anyhow::bail;
+
+#[allow(non_camel_case_types)]
+type Display = c_void;
+#[allow(non_camel_case_types)]
+type Window = c_ulong;
+
+extern "C" {
+    fn XOpenDisplay(display_name: *const c_uchar) -> *mut Display;
+    fn XCloseDisplay(display: *mut Display) -> c_int;
+    fn XDefaultRootWindow(display: *mut Display) -> Window;
+    fn XDefaultScreen(display: *mut Display) -> c_int;
+    fn XDisplayWidth(display: *mut Display, screen_number: c_int) -> c_int;
+    fn XDisplayHeight(display: *mut Display, screen_number: c_int) -> c_int;
+    fn XDisplayWidthMM(display: *mut Display, screen_number: c_int) -> c_int;
+    fn XDisplayHeightMM(display: *mut Display, screen_number: c_int) -> c_int;
+
+    fn XRRSetScreenSize(display: *mut Display, window: Window, width: c_int, height: c_int, mm_width: c_int, mm_height: c_int);
+    fn XSync(display: *mut Display, discard: c_int) -> c_int;
+}
+
+/// Grows the X screen so it's at least `min_width` by `min_height` pixels,
+/// leaving it untouched if it's already big enough. Never shrinks the
+/// screen: a transaction only ever computes the bounding box of the items
+/// it touches, which isn't necessarily the bounding box of the whole
+/// layout (an out-of-transaction output left alone could extend further).
+pub fn grow_screen_to_fit(min_width: u32, min_height: u32) -> anyhow::Result<()> {
+    let display = unsafe { XOpenDisplay(std::ptr::null()) };
+    if display.is_null() {
+        bail!("XOpenDisplay failed, is $DISPLAY set?");
+    }
+    let result = (|| {
+        let screen = unsafe { XDefaultScreen(display) };
+        let root = unsafe { XDefaultRootWindow(display) };
+        let current_width = unsafe { XDisplayWidth(display, screen) } as u32;
+        let current_height = unsafe { XDisplayHeight(display, screen) } as u32;
+        let new_width = current_width.max(min_width);
+        let new_height = current_height.max(min_height);
+        if new_width == current_width && new_height == current_height {
+            return Ok(());
+        }
+
+        // XRRSetScreenSize also wants the physical size in millimetres;
+        // there's no meaningful new value to compute, so keep the
+        // reported DPI roughly constant by scaling the existing physical
+        // size by the same factor as the pixel size.
+        let current_width_mm = unsafe { XDisplayWidthMM(display, screen) } as u32;
+        let current_height_mm = unsafe { XDisplayHeightMM(display, screen) } as u32;
+        let new_width_mm = current_width_mm * new_width / current_width.max(1);
+        let new_height_mm = current_height_mm * new_height / current_height.max(1);
+
+        unsafe {
+            XRRSetScreenSize(display, root, new_width as c_int, new_height as c_int, new_width_mm as c_int, new_height_mm as c_int);
+            // XRRSetScreenSize has no reply, and this runs on a separate
+            // connection from the one `XrandrBackend` places CRTCs on - sync
+            // so the resize is guaranteed to have landed on the server
+            // before the caller proceeds to place a CRTC that depends on it.
+            XSync(display, 0);
+        }
+        Ok(())
+    })();
+    unsafe {
+        XCloseDisplay(display);
+    }
+    result
+}
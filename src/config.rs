@@ -0,0 +1,151 @@
+//! The on-disk (RON) configuration format. Kept independent of any backend:
+//! a `Config` describes *what* the user wants, and `crate::backend` is
+//! responsible for figuring out how to get there on X or DRM.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{ModeInfo, MonitorInfo, Rotation, parse_edid};
+
+/// Reads and parses the config file at `path`. Used both for the initial
+/// load and to re-read it on `SIGHUP` in `--watch` mode.
+pub fn load_config(path: &Path) -> anyhow::Result<Config> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading config file {}", path.display()))?;
+    ron::from_str(&text).with_context(|| format!("parsing config file {}", path.display()))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Config {
+    /// Named full-layout descriptions, e.g. "docked" vs "mobile". On each
+    /// (re-)evaluation the profile whose monitors best match what's
+    /// currently connected is selected and applied; any connected monitor
+    /// the chosen profile doesn't reference is disabled.
+    pub profiles: HashMap<String, Profile>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Profile {
+    // Monitor name to desired config.
+    // The key is only used as a fallback identifier: if a monitor's `match` block
+    // identifies it by EDID, the key itself can be anything memorable, since
+    // monitor names aren't guaranteed to be unique between my computers.
+    pub monitors: HashMap<String, MonitorSpec>,
+}
+
+// Desired config for a monitor.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MonitorSpec {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: Option<f64>, // Refresh rate may not match exactly, closest wins.
+
+    #[serde(default)]
+    pub primary: bool,
+    #[serde(default)]
+    pub rotation: Rotation,
+    pub x: i32,
+    pub y: i32,
+
+    // Identifies the physical monitor this spec applies to. When present, takes
+    // priority over matching by the config's map key against the connector name.
+    #[serde(rename = "match", default)]
+    pub edid_match: Option<EdidMatch>,
+}
+
+/// Identifies a physical monitor by its EDID rather than its (possibly
+/// non-unique, possibly machine-dependent) connector name.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EdidMatch {
+    pub manufacturer: Option<String>,
+    pub product: Option<u16>,
+    pub edid_serial: Option<String>,
+}
+
+impl EdidMatch {
+    /// Whether `edid` satisfies every field this match specifies. A match with
+    /// no fields set matches nothing, to avoid an empty `match: {}` silently
+    /// matching every monitor.
+    fn matches(&self, edid: &edid::EDID) -> bool {
+        if self.manufacturer.is_none() && self.product.is_none() && self.edid_serial.is_none() {
+            return false;
+        }
+        self.manufacturer
+            .as_ref()
+            .is_none_or(|m| *m == edid.header.vendor)
+            && self.product.is_none_or(|p| p == edid.header.product)
+            && self.edid_serial.as_ref().is_none_or(|s| {
+                edid.descriptors
+                    .iter()
+                    .any(|d| matches!(d, edid::Descriptor::SerialNumber(serial) if serial == s))
+            })
+    }
+}
+
+impl MonitorSpec {
+    /// Picks the single best mode matching this spec's resolution: closest
+    /// refresh rate to `refresh_rate` when specified, otherwise the highest
+    /// available refresh rate, breaking remaining ties deterministically by
+    /// mode id.
+    pub fn select_mode<'a>(&self, modes: &'a [ModeInfo]) -> Option<&'a ModeInfo> {
+        modes
+            .iter()
+            .filter(|m| (self.width, self.height) == (m.width, m.height))
+            .min_by(|a, b| self.mode_rank(a).total_cmp(&self.mode_rank(b)).then(a.id.cmp(&b.id)))
+    }
+
+    /// Sort key for mode selection: lower is better.
+    fn mode_rank(&self, mode: &ModeInfo) -> f64 {
+        match self.refresh_rate {
+            Some(r) => f64::abs(r - mode.refresh_rate),
+            None => -mode.refresh_rate,
+        }
+    }
+}
+
+/// Resolves the `MonitorSpec` for `monitor` within `profile`, preferring an
+/// EDID match over the map key matching the connector name, since the
+/// latter isn't guaranteed stable across machines.
+pub fn resolve_monitor_spec<'a>(profile: &'a Profile, monitor: &MonitorInfo) -> Option<&'a MonitorSpec> {
+    if let Some(edid) = monitor.edid.as_deref().and_then(|e| parse_edid(e).ok()) {
+        if let Some(spec) = profile
+            .monitors
+            .values()
+            .find(|spec| spec.edid_match.as_ref().is_some_and(|m| m.matches(&edid)))
+        {
+            return Some(spec);
+        }
+    }
+    profile.monitors.get(&monitor.name)
+}
+
+/// Picks the profile whose monitors best match the currently connected set.
+/// Each declared monitor that resolves to a connected one scores +1 (and -1
+/// if it doesn't), and each connected monitor the profile doesn't reference
+/// scores -1, so the best-matching profile is the one that both accounts
+/// for what's plugged in and doesn't declare monitors that aren't there.
+/// Ties are broken deterministically by profile name.
+pub fn select_profile<'a>(config: &'a Config, monitors: &[MonitorInfo]) -> Option<(&'a str, &'a Profile)> {
+    config
+        .profiles
+        .iter()
+        .map(|(name, profile)| (name.as_str(), profile))
+        .max_by_key(|(name, profile)| (profile_score(profile, monitors), std::cmp::Reverse(*name)))
+}
+
+fn profile_score(profile: &Profile, monitors: &[MonitorInfo]) -> i64 {
+    // Assumes at most one connected monitor resolves to any given spec,
+    // which holds as long as EDID/name matches are kept unambiguous within
+    // a profile; `covered` then doubles as "how many declared monitors are
+    // present".
+    let covered = monitors
+        .iter()
+        .filter(|m| resolve_monitor_spec(profile, m).is_some())
+        .count() as i64;
+    let declared = profile.monitors.len() as i64;
+    let unmatched_declared = declared - covered.min(declared);
+    let unmatched_connected = monitors.len() as i64 - covered;
+    covered - unmatched_declared - unmatched_connected
+}
@@ -0,0 +1,106 @@
+//! Daemon lifecycle: double-fork backgrounding, stdio redirection, and a
+//! self-pipe that funnels SIGHUP/SIGTERM/SIGINT into the same poll loop used
+//! for RandR events, so reloads and topology changes are serviced from one
+//! event loop.
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use anyhow::{Context, bail};
+
+/// Double-forks, detaches from the controlling terminal, and redirects
+/// stdio to `/dev/null`. The caller should only write its pid file after
+/// this returns, so the file records the final daemon pid rather than an
+/// intermediate process that's about to exit.
+pub fn daemonize() -> anyhow::Result<()> {
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } < 0 {
+        bail!("setsid() failed: {}", std::io::Error::last_os_error());
+    }
+    // Fork again so the daemon can never reacquire a controlling terminal
+    // (only a session leader can do that).
+    fork_and_exit_parent()?;
+    if unsafe { libc::chdir(c"/".as_ptr()) } < 0 {
+        bail!("chdir(\"/\") failed: {}", std::io::Error::last_os_error());
+    }
+    redirect_stdio_to_dev_null()
+}
+
+fn fork_and_exit_parent() -> anyhow::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => bail!("fork() failed: {}", std::io::Error::last_os_error()),
+        0 => Ok(()),                 // child continues on
+        _ => std::process::exit(0), // parent's job is done
+    }
+}
+
+fn redirect_stdio_to_dev_null() -> anyhow::Result<()> {
+    let dev_null = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("opening /dev/null")?;
+    let fd = dev_null.as_raw_fd();
+    for target in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(fd, target) } < 0 {
+            bail!("dup2 onto fd {target} failed: {}", std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Write end of the self-pipe, stashed here so the signal handler (which
+/// can't safely close over anything) can reach it.
+static SIGNAL_PIPE_WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Installs handlers for SIGHUP/SIGTERM/SIGINT that each write the signal
+/// number to a pipe, and returns the pipe's read end. Add it to the same
+/// poll set as the RandR event fd to service both from one loop.
+pub fn install_signal_pipe() -> anyhow::Result<RawFd> {
+    let mut fds = [0 as RawFd; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        bail!("pipe() failed: {}", std::io::Error::last_os_error());
+    }
+    let [read_fd, write_fd] = fds;
+    // The read end is drained in a loop until it's empty; it must be
+    // non-blocking or the final read() of each batch blocks forever once
+    // there's nothing left to read.
+    if unsafe { libc::fcntl(read_fd, libc::F_SETFL, libc::O_NONBLOCK) } < 0 {
+        bail!("fcntl(O_NONBLOCK) on signal pipe failed: {}", std::io::Error::last_os_error());
+    }
+    SIGNAL_PIPE_WRITE_FD.store(write_fd, Ordering::SeqCst);
+
+    for signal in [libc::SIGHUP, libc::SIGTERM, libc::SIGINT] {
+        unsafe {
+            libc::signal(signal, handle_signal as libc::sighandler_t);
+        }
+    }
+    Ok(read_fd)
+}
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    let fd = SIGNAL_PIPE_WRITE_FD.load(Ordering::SeqCst);
+    if fd < 0 {
+        return;
+    }
+    // Only async-signal-safe calls allowed here: write() the raw signal
+    // number as a single byte, best-effort.
+    let byte = signal as u8;
+    unsafe {
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+    }
+}
+
+/// Drains the self-pipe, returning every signal number that arrived.
+pub fn drain_signals(read_fd: RawFd) -> Vec<libc::c_int> {
+    let mut signals = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let n = unsafe { libc::read(read_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n <= 0 {
+            break;
+        }
+        signals.extend(buf[..n as usize].iter().map(|&b| b as libc::c_int));
+    }
+    signals
+}